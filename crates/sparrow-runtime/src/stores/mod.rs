@@ -0,0 +1,5 @@
+pub mod object_store_url;
+pub mod object_stores;
+
+pub use object_store_url::ObjectStoreUrl;
+pub use object_stores::{Error, ObjectStoreRegistry};
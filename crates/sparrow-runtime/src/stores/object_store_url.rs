@@ -0,0 +1,276 @@
+use std::str::FromStr;
+
+use derive_more::Display;
+use error_stack::{IntoReport, ResultExt};
+use object_store::path::Path;
+use url::Url;
+
+use super::object_stores::Error;
+
+/// A URL identifying an object (or prefix) within an object store.
+///
+/// This wraps the parsed `url::Url` so that we can derive both the
+/// [`ObjectStoreKey`] (used to look up or create the underlying store) and
+/// the object-store-relative [`Path`] from it.
+#[derive(Display, Debug, Clone, PartialEq, Eq, Hash)]
+#[display(fmt = "{url}")]
+pub struct ObjectStoreUrl {
+    url: Url,
+}
+
+impl FromStr for ObjectStoreUrl {
+    type Err = error_stack::Report<Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(s)
+            .into_report()
+            .change_context_lazy(|| Error::InvalidUrl(s.to_owned()))?;
+        Ok(Self { url })
+    }
+}
+
+impl ObjectStoreUrl {
+    /// The [`ObjectStoreKey`] this URL should be served by.
+    pub fn key(&self) -> error_stack::Result<ObjectStoreKey, Error> {
+        match self.url.scheme() {
+            "file" | "" => Ok(ObjectStoreKey::Local),
+            "memory" | "mem" => Ok(ObjectStoreKey::Memory),
+            "s3" | "s3a" => {
+                let bucket = self
+                    .url
+                    .host_str()
+                    .ok_or_else(|| Error::UrlMissingHost(self.url.clone()))?
+                    .to_owned();
+                let mut region = None;
+                let mut endpoint = None;
+                let mut allow_http = false;
+                let mut virtual_hosted_style_request = false;
+                for (key, value) in self.url.query_pairs() {
+                    match key.as_ref() {
+                        "region" => region = Some(value.into_owned()),
+                        "endpoint" => endpoint = Some(value.into_owned()),
+                        "allow_http" => allow_http = value == "true",
+                        "virtual_hosted_style_request" => {
+                            virtual_hosted_style_request = value == "true"
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(ObjectStoreKey::Aws {
+                    bucket,
+                    region,
+                    virtual_hosted_style_request,
+                    endpoint,
+                    allow_http,
+                })
+            }
+            "gs" | "gcs" => {
+                let bucket = self
+                    .url
+                    .host_str()
+                    .ok_or_else(|| Error::UrlMissingHost(self.url.clone()))?
+                    .to_owned();
+                Ok(ObjectStoreKey::Gcs { bucket })
+            }
+            "az" => {
+                let container = self
+                    .url
+                    .host_str()
+                    .ok_or_else(|| Error::UrlMissingHost(self.url.clone()))?
+                    .to_owned();
+                // The account is usually supplied by the environment (e.g.
+                // `AZURE_STORAGE_ACCOUNT_NAME`), matching arrow-rs's usual
+                // `az://<container>/<path>` convention; an explicit `account`
+                // query parameter overrides that when present.
+                let account = self
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == "account")
+                    .map(|(_, value)| value.into_owned());
+                Ok(ObjectStoreKey::Azure { container, account })
+            }
+            "abfs" | "abfss" => {
+                // `abfs://<container>@<account>.dfs.core.windows.net/...` --
+                // the `@` is the URL's userinfo delimiter, so the container
+                // lives in `username()`, not `host_str()`.
+                let container = self.url.username();
+                if container.is_empty() {
+                    return Err(Error::InvalidUrl(format!(
+                        "expected '<container>@<account>...' in '{}'",
+                        self.url
+                    )))
+                    .into_report();
+                }
+                let host = self
+                    .url
+                    .host_str()
+                    .ok_or_else(|| Error::UrlMissingHost(self.url.clone()))?;
+                let account = host.split('.').next().unwrap_or(host).to_owned();
+                Ok(ObjectStoreKey::Azure {
+                    container: container.to_owned(),
+                    account: Some(account),
+                })
+            }
+            "http" | "https" => {
+                let mut base_url = self.url.clone();
+                base_url.set_query(None);
+                base_url.set_path("");
+                Ok(ObjectStoreKey::Http {
+                    base_url: base_url.to_string(),
+                })
+            }
+            _ => Err(Error::UrlUnsupportedScheme(self.url.clone())).into_report(),
+        }
+    }
+
+    /// The object-store-relative path this URL points to.
+    pub fn path(&self) -> error_stack::Result<Path, Error> {
+        Path::parse(self.url.path())
+            .into_report()
+            .change_context_lazy(|| Error::UrlInvalidPath(self.url.clone()))
+    }
+}
+
+/// Key identifying a distinct object store configuration.
+///
+/// Two URLs that map to the same [`ObjectStoreKey`] should share the same
+/// underlying `ObjectStore` client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ObjectStoreKey {
+    Local,
+    Memory,
+    Aws {
+        bucket: String,
+        region: Option<String>,
+        virtual_hosted_style_request: bool,
+        /// Custom endpoint for S3-compatible stores (MinIO, Cloudflare R2, Ceph, ...).
+        endpoint: Option<String>,
+        /// Whether to allow plaintext HTTP when talking to `endpoint`.
+        allow_http: bool,
+    },
+    Gcs {
+        bucket: String,
+    },
+    Azure {
+        container: String,
+        /// Storage account, if overridden explicitly; otherwise `from_env`
+        /// resolves it (e.g. from `AZURE_STORAGE_ACCOUNT_NAME`).
+        account: Option<String>,
+    },
+    /// A plain HTTP/WebDAV server, e.g. a CDN-fronted static bucket.
+    Http {
+        base_url: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{ObjectStoreKey, ObjectStoreUrl};
+
+    #[test]
+    fn test_key_local() {
+        let url = ObjectStoreUrl::from_str("file:///foo").unwrap();
+        assert_eq!(url.key().unwrap(), ObjectStoreKey::Local);
+    }
+
+    #[test]
+    fn test_key_s3() {
+        let url = ObjectStoreUrl::from_str("s3://my-bucket/foo").unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Aws {
+                bucket: "my-bucket".to_owned(),
+                region: None,
+                virtual_hosted_style_request: false,
+                endpoint: None,
+                allow_http: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_s3_custom_endpoint() {
+        let url = ObjectStoreUrl::from_str(
+            "s3://my-bucket/foo?endpoint=http://localhost:9000&allow_http=true",
+        )
+        .unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Aws {
+                bucket: "my-bucket".to_owned(),
+                region: None,
+                virtual_hosted_style_request: false,
+                endpoint: Some("http://localhost:9000".to_owned()),
+                allow_http: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_gcs() {
+        let url = ObjectStoreUrl::from_str("gs://my-bucket/foo").unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Gcs {
+                bucket: "my-bucket".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_azure_az_scheme() {
+        let url = ObjectStoreUrl::from_str("az://my-container/foo?account=my-account").unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Azure {
+                container: "my-container".to_owned(),
+                account: Some("my-account".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_azure_az_scheme_account_from_env() {
+        let url = ObjectStoreUrl::from_str("az://my-container/foo").unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Azure {
+                container: "my-container".to_owned(),
+                account: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_azure_abfs_scheme() {
+        let url =
+            ObjectStoreUrl::from_str("abfs://my-container@my-account.dfs.core.windows.net/foo")
+                .unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Azure {
+                container: "my-container".to_owned(),
+                account: Some("my-account".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_http() {
+        let url = ObjectStoreUrl::from_str("https://example.com/data/foo.parquet").unwrap();
+        assert_eq!(
+            url.key().unwrap(),
+            ObjectStoreKey::Http {
+                base_url: "https://example.com/".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_key_unsupported_scheme() {
+        let url = ObjectStoreUrl::from_str("ftp://my-bucket/foo").unwrap();
+        assert!(url.key().is_err());
+    }
+}
@@ -1,29 +1,84 @@
+use std::ops::Range;
 use std::path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 use derive_more::Display;
 use error_stack::{IntoReport, ResultExt};
-use object_store::ObjectStore;
+use futures::StreamExt;
+use object_store::{GetOptions, GetRange, ObjectStore};
 use tokio::{fs, io::AsyncWriteExt};
 use url::Url;
 
 use super::{object_store_url::ObjectStoreKey, ObjectStoreUrl};
 
+/// Default time a cached object store is trusted before it is rebuilt.
+///
+/// This needs to be shorter than the lifetime of the shortest-lived
+/// credentials we expect to see (e.g. STS tokens, GCS bearer tokens,
+/// instance-metadata credentials) so a cached client never outlives the
+/// credentials it was built with.
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A cached object store, along with when it was created.
+#[derive(Clone)]
+struct CacheEntry {
+    store: Arc<dyn ObjectStore>,
+    inserted_at: Instant,
+}
+
+impl std::fmt::Debug for CacheEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheEntry")
+            .field("store", &self.store)
+            .field("inserted_at", &self.inserted_at)
+            .finish()
+    }
+}
+
+impl CacheEntry {
+    fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            store,
+            inserted_at: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.inserted_at.elapsed() >= ttl
+    }
+}
+
 /// Map from URL scheme to object store for that prefix.
 ///
-/// Currently, we use a single object store or each scheme. This covers
-/// cases like `file:///` using a local file store and `s3://` using an
-/// S3 file store. We may find that it is useful (or necessary) to register
-/// specific object stores for specific prefixes -- for instance, to use
-/// different credentials for different buckets within S3.
+/// Currently, we cache one object store per [`ObjectStoreKey`] (e.g. per S3
+/// bucket and endpoint), built from environment credentials via
+/// [`create_object_store`]. Callers that need
+/// different credentials for different buckets -- e.g. reading from one S3
+/// bucket with one role and writing to another with a different role --
+/// can [`ObjectStoreRegistry::register`] an explicit store for a URL prefix;
+/// registrations take priority over the key-based, environment-derived
+/// cache.
 ///
 /// For now, the registry exists as a cache for the clients due to the overhead
 /// required to create the cache. The future goal for the registry is to
 /// control the number of possibile open connections.
-#[derive(Default, Debug)]
+///
+/// Cached entries are rebuilt once they are older than `ttl`, so temporary
+/// credentials (STS tokens, GCS bearer tokens, instance-metadata creds) don't
+/// get stuck serving requests after they expire.
+#[derive(Debug)]
 pub struct ObjectStoreRegistry {
-    object_stores: DashMap<ObjectStoreKey, Arc<dyn ObjectStore>>,
+    object_stores: DashMap<ObjectStoreKey, CacheEntry>,
+    registrations: DashMap<String, Arc<dyn ObjectStore>>,
+    ttl: Duration,
+}
+
+impl Default for ObjectStoreRegistry {
+    fn default() -> Self {
+        Self::with_ttl(DEFAULT_CACHE_TTL)
+    }
 }
 
 impl ObjectStoreRegistry {
@@ -31,16 +86,82 @@ impl ObjectStoreRegistry {
         Self::default()
     }
 
+    /// Create a registry that rebuilds cached stores older than `ttl`.
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            object_stores: DashMap::new(),
+            registrations: DashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Register an explicit object store for everything under `url_prefix`.
+    ///
+    /// `url_prefix` matches on path-segment boundaries, not raw characters --
+    /// registering `s3://my-bucket` matches `s3://my-bucket/foo` but not
+    /// `s3://my-bucket-2/foo`. Lookups in [`Self::object_store`] check
+    /// registrations before falling back to the key-based, `from_env`-built
+    /// cache, so this is the way to give a specific bucket (or account) its
+    /// own credentials without affecting any other bucket using the same
+    /// scheme.
+    pub fn register(&self, url_prefix: impl Into<String>, object_store: Arc<dyn ObjectStore>) {
+        let url_prefix = url_prefix.into();
+        let url_prefix = url_prefix
+            .strip_suffix('/')
+            .map(str::to_owned)
+            .unwrap_or(url_prefix);
+        self.registrations.insert(url_prefix, object_store);
+    }
+
+    /// The explicitly registered store, if any, whose prefix `url` falls
+    /// under. When multiple registrations match, the longest (most specific)
+    /// prefix wins.
+    fn registered_store(&self, url: &ObjectStoreUrl) -> Option<Arc<dyn ObjectStore>> {
+        let url = url.to_string();
+        self.registrations
+            .iter()
+            .filter(|entry| {
+                let prefix = entry.key().as_str();
+                url == prefix || url.starts_with(&format!("{prefix}/"))
+            })
+            .max_by_key(|entry| entry.key().len())
+            .map(|entry| entry.value().clone())
+    }
+
     pub fn object_store(
         &self,
         url: &ObjectStoreUrl,
     ) -> error_stack::Result<Arc<dyn ObjectStore>, Error> {
+        if let Some(object_store) = self.registered_store(url) {
+            return Ok(object_store);
+        }
+
         let key = url.key()?;
+
+        // Fast path: don't take the entry lock if the cached store is fresh.
+        if let Some(entry) = self.object_stores.get(&key) {
+            if !entry.is_expired(self.ttl) {
+                return Ok(entry.store.clone());
+            }
+        }
+
         match self.object_stores.entry(key) {
-            dashmap::mapref::entry::Entry::Occupied(entry) => Ok(entry.get().clone()),
+            dashmap::mapref::entry::Entry::Occupied(mut entry) => {
+                // Re-check after taking the entry -- another caller may have
+                // already rebuilt this key while we were waiting.
+                if !entry.get().is_expired(self.ttl) {
+                    return Ok(entry.get().store.clone());
+                }
+                let object_store = create_object_store(entry.key())?;
+                let new_entry = CacheEntry::new(object_store);
+                let store = new_entry.store.clone();
+                entry.insert(new_entry);
+                Ok(store)
+            }
             dashmap::mapref::entry::Entry::Vacant(vacant) => {
                 let object_store = create_object_store(vacant.key())?;
-                Ok(vacant.insert(object_store).value().clone())
+                let entry = vacant.insert(CacheEntry::new(object_store));
+                Ok(entry.store.clone())
             }
         }
     }
@@ -75,6 +196,58 @@ impl ObjectStoreRegistry {
             .change_context(Error::Internal)?;
         Ok(())
     }
+
+    /// Download `source_url` to `local_file_path`.
+    ///
+    /// If `range` is given, only that byte range of the object is fetched --
+    /// useful for reading just the footer or row-group headers of a large
+    /// Parquet file without pulling down the whole thing.
+    pub async fn download(
+        &self,
+        source_url: ObjectStoreUrl,
+        local_file_path: &path::Path,
+        range: Option<Range<usize>>,
+    ) -> error_stack::Result<(), Error> {
+        let source_path = source_url.path()?;
+        let object_store = self.object_store(&source_url)?;
+        let options = GetOptions {
+            range: range.map(GetRange::Bounded),
+            ..Default::default()
+        };
+        let get_result = object_store
+            .get_opts(&source_path, options)
+            .await
+            .into_report()
+            .change_context_lazy(|| Error::DownloadingObject {
+                from: source_url.clone(),
+                to: local_file_path.to_owned(),
+            })?;
+        let mut local_file = fs::File::create(local_file_path)
+            .await
+            .into_report()
+            .change_context_lazy(|| Error::DownloadingObject {
+                from: source_url.clone(),
+                to: local_file_path.to_owned(),
+            })?;
+
+        // Stream chunk-by-chunk instead of buffering the whole object, so
+        // memory use stays bounded even for a large Parquet file.
+        let mut chunks = get_result.into_stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk
+                .into_report()
+                .change_context_lazy(|| Error::DownloadingObject {
+                    from: source_url.clone(),
+                    to: local_file_path.to_owned(),
+                })?;
+            local_file
+                .write_all(&chunk)
+                .await
+                .into_report()
+                .change_context(Error::Internal)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Display, Debug)]
@@ -90,7 +263,7 @@ pub enum Error {
     #[display(fmt = "unsupported host '{}' in URL '{_0}", "_0.host().unwrap()")]
     UrlUnsupportedHost(Url),
     #[display(
-        fmt = "unsupported scheme '{}' in URL '{_0}'; expected one of 'file' or 's3'",
+        fmt = "unsupported scheme '{}' in URL '{_0}'; expected one of 'file', 's3', 'gs', 'az', 'abfs', 'http' or 'https'",
         "_0.scheme()"
     )]
     UrlUnsupportedScheme(Url),
@@ -117,15 +290,23 @@ fn create_object_store(key: &ObjectStoreKey) -> error_stack::Result<Arc<dyn Obje
             bucket,
             region,
             virtual_hosted_style_request,
+            endpoint,
+            allow_http,
         } => {
             let builder = object_store::aws::AmazonS3Builder::from_env()
                 .with_bucket_name(bucket)
-                .with_virtual_hosted_style_request(*virtual_hosted_style_request);
+                .with_virtual_hosted_style_request(*virtual_hosted_style_request)
+                .with_allow_http(*allow_http);
             let builder = if let Some(region) = region {
                 builder.with_region(region)
             } else {
                 builder
             };
+            let builder = if let Some(endpoint) = endpoint {
+                builder.with_endpoint(endpoint)
+            } else {
+                builder
+            };
             let object_store = builder
                 .build()
                 .into_report()
@@ -141,12 +322,38 @@ fn create_object_store(key: &ObjectStoreKey) -> error_stack::Result<Arc<dyn Obje
                 .change_context(Error::CreatingObjectStore)?;
             Ok(Arc::new(object_store))
         }
+        ObjectStoreKey::Azure { container, account } => {
+            let builder = object_store::azure::MicrosoftAzureBuilder::from_env()
+                .with_container_name(container);
+            let builder = if let Some(account) = account {
+                builder.with_account(account)
+            } else {
+                builder
+            };
+            let object_store = builder
+                .build()
+                .into_report()
+                .change_context(Error::CreatingObjectStore)?;
+            Ok(Arc::new(object_store))
+        }
+        ObjectStoreKey::Http { base_url } => {
+            let builder = object_store::http::HttpBuilder::new().with_url(base_url);
+            let object_store = builder
+                .build()
+                .into_report()
+                .change_context(Error::CreatingObjectStore)?;
+            Ok(Arc::new(object_store))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use object_store::ObjectStore;
 
     use crate::stores::ObjectStoreUrl;
     use crate::stores::{
@@ -173,6 +380,21 @@ mod tests {
             bucket: "test-bucket".to_string(),
             region: Some("test-region".to_string()),
             virtual_hosted_style_request: true,
+            endpoint: None,
+            allow_http: false,
+        };
+        let object_store = create_object_store(&key).unwrap();
+        assert_eq!(object_store.to_string(), "AmazonS3(test-bucket)")
+    }
+
+    #[test]
+    fn test_create_object_store_aws_builder_custom_endpoint() {
+        let key = ObjectStoreKey::Aws {
+            bucket: "test-bucket".to_string(),
+            region: None,
+            virtual_hosted_style_request: false,
+            endpoint: Some("http://localhost:9000".to_string()),
+            allow_http: true,
         };
         let object_store = create_object_store(&key).unwrap();
         assert_eq!(object_store.to_string(), "AmazonS3(test-bucket)")
@@ -187,6 +409,36 @@ mod tests {
         assert_eq!(object_store.to_string(), "GoogleCloudStorage(test-bucket)")
     }
 
+    #[test]
+    fn test_create_object_store_azure_builder() {
+        let key = ObjectStoreKey::Azure {
+            container: "test-container".to_owned(),
+            account: Some("test-account".to_owned()),
+        };
+        let object_store = create_object_store(&key).unwrap();
+        assert_eq!(object_store.to_string(), "MicrosoftAzure(test-container)")
+    }
+
+    #[test]
+    fn test_create_object_store_azure_builder_account_from_env() {
+        std::env::set_var("AZURE_STORAGE_ACCOUNT_NAME", "test-account");
+        let key = ObjectStoreKey::Azure {
+            container: "test-container".to_owned(),
+            account: None,
+        };
+        let object_store = create_object_store(&key).unwrap();
+        assert_eq!(object_store.to_string(), "MicrosoftAzure(test-container)")
+    }
+
+    #[test]
+    fn test_create_object_store_http_builder() {
+        let key = ObjectStoreKey::Http {
+            base_url: "https://example.com/".to_owned(),
+        };
+        let object_store = create_object_store(&key).unwrap();
+        assert_eq!(object_store.to_string(), "HttpStore")
+    }
+
     #[test]
     fn test_object_store_registry_creates_if_not_exists() {
         let object_store_registry = ObjectStoreRegistry::new();
@@ -202,4 +454,96 @@ mod tests {
         assert!(object_store.is_ok());
         assert!(object_store_registry.object_stores.contains_key(&key));
     }
+
+    #[test]
+    fn test_object_store_registry_prefers_registered_store() {
+        let object_store_registry = ObjectStoreRegistry::new();
+        let url = ObjectStoreUrl::from_str("s3://my-bucket/foo").unwrap();
+
+        let registered: Arc<dyn ObjectStore> = Arc::new(object_store::memory::InMemory::new());
+        object_store_registry.register("s3://my-bucket", registered.clone());
+
+        let resolved = object_store_registry.object_store(&url).unwrap();
+        assert!(Arc::ptr_eq(&resolved, &registered));
+
+        // A URL under an unregistered bucket still falls back to `from_env`,
+        // i.e. doesn't go through the registration.
+        let other_url = ObjectStoreUrl::from_str("s3://other-bucket/foo").unwrap();
+        let other_resolved = object_store_registry.object_store(&other_url).unwrap();
+        assert!(!Arc::ptr_eq(&other_resolved, &registered));
+        let other_key = other_url.key().unwrap();
+        assert!(object_store_registry.object_stores.contains_key(&other_key));
+    }
+
+    #[test]
+    fn test_object_store_registry_registration_respects_segment_boundary() {
+        let object_store_registry = ObjectStoreRegistry::new();
+
+        let registered: Arc<dyn ObjectStore> = Arc::new(object_store::memory::InMemory::new());
+        object_store_registry.register("s3://my-bucket", registered.clone());
+
+        // "my-bucket-2" is a different bucket that merely shares a string
+        // prefix with "my-bucket" -- it must not pick up the registration.
+        let sibling_url = ObjectStoreUrl::from_str("s3://my-bucket-2/foo").unwrap();
+        let resolved = object_store_registry.object_store(&sibling_url).unwrap();
+        assert!(!Arc::ptr_eq(&resolved, &registered));
+
+        let sibling_key = sibling_url.key().unwrap();
+        assert!(object_store_registry
+            .object_stores
+            .contains_key(&sibling_key));
+    }
+
+    #[test]
+    fn test_object_store_registry_rebuilds_expired_entry() {
+        let object_store_registry = ObjectStoreRegistry::with_ttl(Duration::from_secs(0));
+        let url = ObjectStoreUrl::from_str("file:///foo").unwrap();
+        let key = url.key().unwrap();
+
+        let first_store = object_store_registry.object_store(&url).unwrap();
+        let first_inserted_at = object_store_registry
+            .object_stores
+            .get(&key)
+            .unwrap()
+            .inserted_at;
+
+        // With a zero TTL, the cached entry is immediately expired, so the
+        // next lookup should rebuild it rather than reuse the stale entry.
+        let second_store = object_store_registry.object_store(&url).unwrap();
+        let second_inserted_at = object_store_registry
+            .object_stores
+            .get(&key)
+            .unwrap()
+            .inserted_at;
+
+        assert!(second_inserted_at >= first_inserted_at);
+        assert!(!Arc::ptr_eq(&first_store, &second_store));
+    }
+
+    #[tokio::test]
+    async fn test_download_round_trip() {
+        let object_store_registry = ObjectStoreRegistry::new();
+        let url = ObjectStoreUrl::from_str("mem:///foo.txt").unwrap();
+        let object_store = object_store_registry.object_store(&url).unwrap();
+        object_store
+            .put(&url.path().unwrap(), "hello world".into())
+            .await
+            .unwrap();
+
+        let local_path = std::env::temp_dir().join("test_download_round_trip_full.txt");
+        object_store_registry
+            .download(url.clone(), &local_path, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&local_path).unwrap(), "hello world");
+        std::fs::remove_file(&local_path).unwrap();
+
+        let local_path = std::env::temp_dir().join("test_download_round_trip_range.txt");
+        object_store_registry
+            .download(url, &local_path, Some(0..5))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&local_path).unwrap(), "hello");
+        std::fs::remove_file(&local_path).unwrap();
+    }
 }